@@ -4,28 +4,285 @@ extern crate pretty_env_logger;
 extern crate serde;
 #[macro_use] extern crate serde_derive;
 extern crate warp;
-extern crate hyper;
+extern crate futures;
+extern crate reqwest;
+extern crate jsonwebtoken;
+extern crate flate2;
 
 use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
-use warp::{http::StatusCode, Filter};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use warp::{http::StatusCode, Filter, Rejection, Reply};
 
-use hyper::Client;
-use hyper::rt::{Future, Stream};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use futures::{future, Future};
+use jsonwebtoken::{decode, encode, Header, Validation};
+use serde::Serialize;
 
 
-/// So we don't have to tackle how different database work, we'll just use
-/// a simple in-memory DB, a vector synchronized by a mutex.
-type Db = Arc<Mutex<Vec<Todo>>>;
+/// The shared storage handle threaded through every handler.
+///
+/// Which concrete `Store` sits behind it is decided at startup from the
+/// `TODOS_STORE` env var, so the handlers stay oblivious to whether todos
+/// live in memory or on disk.
+type Db = Arc<dyn Store>;
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 struct Todo {
     id: u64,
     text: String,
     completed: bool,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+/// The query parameters accepted by `GET /todos`.
+///
+/// All fields are optional so the endpoint keeps working for clients that
+/// don't care about paging.
+#[derive(Debug, Deserialize)]
+struct ListOptions {
+    offset: Option<usize>,
+    limit: Option<usize>,
+    completed: Option<bool>,
+}
+
+/// A boxed future returned by the `Store` methods. IO-backed stores may fail,
+/// so the error is an `io::Error`; purely in-memory stores never error.
+type StoreFuture<T> = Box<dyn Future<Item = T, Error = io::Error> + Send>;
+
+/// Abstracts over the backing storage for Todos. Handlers talk to this trait
+/// instead of locking a vector directly, so the storage can be swapped without
+/// touching the route chain.
+trait Store: Send + Sync {
+    /// Return a snapshot of all stored todos.
+    fn list(&self) -> StoreFuture<Vec<Todo>>;
+    /// Insert a new todo, resolving to `false` if the id already exists.
+    fn create(&self, todo: Todo) -> StoreFuture<bool>;
+    /// Replace the todo with the given id, resolving to `false` if absent.
+    fn update(&self, id: u64, todo: Todo) -> StoreFuture<bool>;
+    /// Remove the todo with the given id, resolving to `false` if absent.
+    fn delete(&self, id: u64) -> StoreFuture<bool>;
+}
+
+/// The original volatile backend: a vector synchronized by a mutex.
+struct MemoryStore {
+    todos: Arc<Mutex<Vec<Todo>>>,
+}
+
+impl MemoryStore {
+    fn new() -> MemoryStore {
+        MemoryStore {
+            todos: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+}
+
+impl Store for MemoryStore {
+    fn list(&self) -> StoreFuture<Vec<Todo>> {
+        Box::new(future::ok(self.todos.lock().unwrap().clone()))
+    }
+
+    fn create(&self, todo: Todo) -> StoreFuture<bool> {
+        let mut vec = self.todos.lock().unwrap();
+        if vec.iter().any(|t| t.id == todo.id) {
+            return Box::new(future::ok(false));
+        }
+        vec.push(todo);
+        Box::new(future::ok(true))
+    }
+
+    fn update(&self, id: u64, todo: Todo) -> StoreFuture<bool> {
+        let mut vec = self.todos.lock().unwrap();
+        for slot in vec.iter_mut() {
+            if slot.id == id {
+                *slot = todo;
+                return Box::new(future::ok(true));
+            }
+        }
+        Box::new(future::ok(false))
+    }
+
+    fn delete(&self, id: u64) -> StoreFuture<bool> {
+        let mut vec = self.todos.lock().unwrap();
+        let len = vec.len();
+        vec.retain(|t| t.id != id);
+        Box::new(future::ok(vec.len() != len))
+    }
+}
+
+/// A file-backed backend that mirrors an in-memory vector to a JSON file.
+///
+/// The vector is loaded once at startup and rewritten under the lock after
+/// every mutation, so todos survive a restart.
+struct FileStore {
+    path: PathBuf,
+    todos: Arc<Mutex<Vec<Todo>>>,
+}
+
+impl FileStore {
+    fn new<P: Into<PathBuf>>(path: P) -> FileStore {
+        let path = path.into();
+        let todos = match fs::read(&path) {
+            // Present and parseable: use it. A present-but-corrupt file is a
+            // hard error — starting empty would let the next write clobber it.
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|e| {
+                panic!("todos file {} is present but not valid JSON: {}", path.display(), e)
+            }),
+            // Absent file just means "start empty".
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            // Any other read error (permissions, …) is also fatal.
+            Err(e) => panic!("failed to read todos file {}: {}", path.display(), e),
+        };
+        FileStore {
+            path,
+            todos: Arc::new(Mutex::new(todos)),
+        }
+    }
+
+    /// Serialize the current vector to the backing file. Must be called while
+    /// holding the lock so the file never reflects a torn update.
+    fn persist(&self, todos: &[Todo]) -> io::Result<()> {
+        let bytes = serde_json::to_vec(todos)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        fs::write(&self.path, bytes)
+    }
+}
+
+impl Store for FileStore {
+    fn list(&self) -> StoreFuture<Vec<Todo>> {
+        Box::new(future::ok(self.todos.lock().unwrap().clone()))
+    }
+
+    fn create(&self, todo: Todo) -> StoreFuture<bool> {
+        let mut vec = self.todos.lock().unwrap();
+        if vec.iter().any(|t| t.id == todo.id) {
+            return Box::new(future::ok(false));
+        }
+        // Mutate, then persist; roll the vec back if the write fails so the
+        // in-memory state never drifts from what's on disk.
+        vec.push(todo);
+        if let Err(e) = self.persist(&vec) {
+            vec.pop();
+            return Box::new(future::err(e));
+        }
+        Box::new(future::ok(true))
+    }
+
+    fn update(&self, id: u64, todo: Todo) -> StoreFuture<bool> {
+        let mut vec = self.todos.lock().unwrap();
+        let pos = match vec.iter().position(|t| t.id == id) {
+            Some(pos) => pos,
+            None => return Box::new(future::ok(false)),
+        };
+        let previous = std::mem::replace(&mut vec[pos], todo);
+        if let Err(e) = self.persist(&vec) {
+            vec[pos] = previous;
+            return Box::new(future::err(e));
+        }
+        Box::new(future::ok(true))
+    }
+
+    fn delete(&self, id: u64) -> StoreFuture<bool> {
+        let mut vec = self.todos.lock().unwrap();
+        let pos = match vec.iter().position(|t| t.id == id) {
+            Some(pos) => pos,
+            None => return Box::new(future::ok(false)),
+        };
+        let removed = vec.remove(pos);
+        if let Err(e) = self.persist(&vec) {
+            vec.insert(pos, removed);
+            return Box::new(future::err(e));
+        }
+        Box::new(future::ok(true))
+    }
+}
+
+/// Build the storage backend from the `TODOS_STORE` env var.
+///
+/// `TODOS_STORE=file:/path/todos.json` selects the file-backed store;
+/// anything else (including an unset var) falls back to the in-memory store.
+fn make_store() -> Arc<dyn Store> {
+    match env::var("TODOS_STORE") {
+        Ok(ref val) if val.starts_with("file:") => {
+            let path = &val["file:".len()..];
+            info!("using file store at {}", path);
+            Arc::new(FileStore::new(path))
+        }
+        _ => {
+            info!("using in-memory store");
+            Arc::new(MemoryStore::new())
+        }
+    }
+}
+
+/// Configuration for the JWT-based auth, loaded once from the environment.
+struct AuthConfig {
+    secret: String,
+    username: String,
+    password: String,
+    ttl_seconds: u64,
+}
+
+impl AuthConfig {
+    fn from_env() -> AuthConfig {
+        AuthConfig {
+            secret: env::var("JWT_SECRET").unwrap_or_else(|_| "change-me".to_string()),
+            username: env::var("AUTH_USER").unwrap_or_else(|_| "admin".to_string()),
+            password: env::var("AUTH_PASSWORD").unwrap_or_else(|_| "password".to_string()),
+            ttl_seconds: 3600,
+        }
+    }
+}
+
+/// The signed claims carried by an issued token.
+#[derive(Debug, Deserialize, Serialize)]
+struct Claims {
+    sub: String,
+    exp: usize,
+}
+
+/// Body of `POST /auth/login`.
+#[derive(Deserialize)]
+struct LoginRequest {
+    username: String,
+    password: String,
+}
+
+/// Successful response of `POST /auth/login`.
+#[derive(Serialize)]
+struct LoginResponse {
+    token: String,
+}
+
+/// Parse and verify the `Authorization: Bearer <jwt>` header against the
+/// configured secret, resolving to the decoded claims or a `401` rejection.
+///
+/// The header is taken as optional so a missing `Authorization` (the common
+/// anonymous-write case) maps to our `Unauthorized` rejection rather than
+/// warp's built-in missing-header rejection, which would surface as a 500.
+fn verify_token(header: Option<String>, config: Arc<AuthConfig>) -> Result<Claims, Rejection> {
+    let header = match header {
+        Some(header) => header,
+        None => return Err(warp::reject::custom(Unauthorized)),
+    };
+
+    if !header.starts_with("Bearer ") {
+        return Err(warp::reject::custom(Unauthorized));
+    }
+
+    let token = header[7..].trim();
+    // `Validation::default()` checks the HS256 signature and the `exp` claim.
+    decode::<Claims>(token, config.secret.as_bytes(), &Validation::default())
+        .map(|data| data.claims)
+        .map_err(|_| warp::reject::custom(Unauthorized))
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
 struct Post {
     #[serde(rename = "userId")]
     user_id: i32,
@@ -56,8 +313,22 @@ fn main() {
 
     // Turn our "state", our db, into a Filter so we can combine it
     // easily with others...
-    let db = Arc::new(Mutex::new(Vec::<Todo>::new()));
-    let db = warp::any().map(move || db.clone());
+    let store = make_store();
+    let db = warp::any().map(move || store.clone());
+
+    // Shared auth configuration, turned into a Filter like the db above...
+    let auth_config = Arc::new(AuthConfig::from_env());
+    let auth_config = warp::any().map(move || auth_config.clone());
+
+    // The `auth` filter: extracts and verifies the bearer token, yielding the
+    // decoded `Claims` to the protected handlers (or a `401` rejection).
+    //
+    // The header is taken as *optional* so a missing `Authorization` rejects
+    // with our `Unauthorized` (401) rather than warp's missing-header
+    // rejection, which `handle_rejection` would map to a 500.
+    let auth = warp::header::optional::<String>("authorization")
+        .and(auth_config.clone())
+        .and_then(verify_token);
 
     // Just the path segment "todos"...
     let todos = warp::path("todos");
@@ -75,60 +346,100 @@ fn main() {
     // When accepting a body, we want a JSON body
     // (and to reject huge payloads)...
     let json_body = warp::body::content_length_limit(1024 * 16)
-        .and(warp::body::json());
+        .and(warp::body::json::<Todo>());
+
+    // The login route takes a different body shape, so it needs its own filter
+    // (a single `json_body` can only ever infer one `T`).
+    let login_body = warp::body::content_length_limit(1024 * 16)
+        .and(warp::body::json::<LoginRequest>());
 
     // Next, we'll define each our 4 endpoints:
 
     // `GET /todos`
     let list = warp::get2()
         .and(todos_index)
+        .and(warp::query::<ListOptions>())
+        .and(warp::header::optional::<String>("accept-encoding"))
         .and(db.clone())
-        .map(list_todos);
+        .and_then(list_todos);
 
-    // `POST /todos`
+    // `POST /todos` — requires a valid bearer token.
     let create = warp::post2()
         .and(todos_index)
         .and(json_body)
+        .and(auth.clone())
         .and(db.clone())
         .and_then(create_todo);
 
-    // `PUT /todos/:id`
+    // `PUT /todos/:id` — requires a valid bearer token.
     let update = warp::put2()
         .and(todos_id)
         .and(json_body)
+        .and(auth.clone())
         .and(db.clone())
         .and_then(update_todo);
 
-    // `DELETE /todos/:id`
+    // `DELETE /todos/:id` — requires a valid bearer token.
     let delete = warp::delete2()
         .and(todos_id)
+        .and(auth.clone())
         .and(db.clone())
         .and_then(delete_todo);
+
+    // `POST /auth/login` — exchange credentials for a signed JWT.
+    let login_route = warp::post2()
+        .and(warp::path("auth"))
+        .and(warp::path("login"))
+        .and(warp::path::end())
+        .and(login_body)
+        .and(auth_config.clone())
+        .and_then(login);
     
+    // Shared, cached upstream client, turned into a Filter like the db above...
+    let posts_client = PostsClient::from_env();
+    let posts_client = warp::any().map(move || posts_client.clone());
+
     let posts = warp::path("posts");
     let posts_index = posts.and(warp::path::end());
 
     let posts_list = warp::get2()
         .and(posts_index)
-        .and_then(|| {
-            debug!("list_posts");
-
-            let posts_url = "http://jsonplaceholder.typicode.com/posts".parse().unwrap();
-
-            fetch_json(posts_url)
-                .map(|posts| warp::reply::json(&posts))
-                .map_err(|_| warp::reject::not_found())
-        });
+        .and(warp::header::optional::<String>("accept-encoding"))
+        .and(posts_client.clone())
+        .and_then(list_posts);
 
     // Combine our endpoints, since we want requests to match any of them:
     let api = list
         .or(create)
         .or(update)
         .or(delete)
+        .or(login_route)
         .or(posts_list);
 
-    // View access logs by setting `RUST_LOG=todos`.
-    let routes = api.with(warp::log("todos"));
+    // Cross-origin support so browser front-ends can call the API directly.
+    // Allowed origins come from `CORS_ALLOW_ORIGINS` (comma-separated); when
+    // unset we fall back to allowing any origin.
+    let cors = warp::cors()
+        .allow_methods(vec!["GET", "POST", "PUT", "DELETE"])
+        .allow_headers(vec!["content-type", "authorization"]);
+    let cors = match env::var("CORS_ALLOW_ORIGINS") {
+        Ok(ref origins) if !origins.is_empty() => {
+            let mut cors = cors;
+            for origin in origins.split(',') {
+                cors = cors.allow_origin(origin.trim());
+            }
+            cors
+        }
+        _ => cors.allow_any_origin(),
+    };
+
+    // View access logs by setting `RUST_LOG=todos`. gzip compression of the
+    // large `GET` payloads is handled per-handler (see `json_maybe_gzip`),
+    // since warp 0.1 has no `compression` module.
+    let routes = api
+        .recover(handle_rejection)
+        .with(cors)
+        .with(warp::log("todos"));
 
     // Start up the server...
     warp::serve(routes)
@@ -140,120 +451,358 @@ fn main() {
 // with the exact arguments we'd expect from each filter in the chain.
 // No tuples are needed, it's auto flattened for the functions.
 
+/// Serialize `value` as a JSON reply, gzip-compressing the body when the
+/// client advertised `gzip` in `Accept-Encoding`. warp 0.1 has no compression
+/// middleware, so the large `GET` payloads handle it here.
+fn json_maybe_gzip<T: Serialize>(value: &T, accept_encoding: Option<String>) -> warp::reply::Response {
+    use warp::http::{header, Response};
+
+    let body = serde_json::to_vec(value).unwrap_or_default();
+    let wants_gzip = accept_encoding
+        .map(|h| h.to_lowercase().contains("gzip"))
+        .unwrap_or(false);
+
+    if wants_gzip {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        if let Ok(compressed) = encoder.write_all(&body).and_then(|_| encoder.finish()) {
+            return Response::builder()
+                .header(header::CONTENT_TYPE, "application/json")
+                .header(header::CONTENT_ENCODING, "gzip")
+                .body(compressed.into())
+                .unwrap();
+        }
+    }
+
+    Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(body.into())
+        .unwrap()
+}
+
 /// GET /todos
-fn list_todos(db: Db) -> impl warp::Reply {
-    // Just return a JSON array of all Todos.
-    warp::reply::json(&*db.lock().unwrap())
+fn list_todos(opts: ListOptions, accept_encoding: Option<String>, db: Db) -> impl Future<Item = impl warp::Reply, Error = Rejection> {
+    db.list().map_err(reject_store_error).map(move |todos| {
+        let result: Vec<Todo> = todos
+            .into_iter()
+            .filter(|todo| match opts.completed {
+                Some(completed) => todo.completed == completed,
+                None => true,
+            })
+            .skip(opts.offset.unwrap_or(0))
+            .take(opts.limit.unwrap_or(usize::MAX))
+            .collect();
+
+        json_maybe_gzip(&result, accept_encoding)
+    })
 }
 
-fn fetch_json(url: hyper::Uri) -> impl Future<Item=Vec<Post>, Error=FetchError> {
-    let client = Client::new();
+/// GET /posts — proxy (and cache) the upstream posts service.
+fn list_posts(accept_encoding: Option<String>, client: PostsClient) -> impl Future<Item = impl warp::Reply, Error = Rejection> {
+    debug!("list_posts");
 
     client
-        // Fetch the url...
-        .get(url)
-        // And then, if we get a response back...
-        .and_then(|res| {
-            // asynchronously concatenate chunks of the body
-            res.into_body().concat2()
-        })
-        .from_err::<FetchError>()
-        // use the body after concatenation
-        .and_then(|body| {
-            // try to parse as json with serde_json
-            let users = serde_json::from_slice(&body)?;
-
-            Ok(users)
-        })
-        .from_err()
+        .posts()
+        .map(move |posts| json_maybe_gzip(&posts, accept_encoding))
+        // Surface upstream failures distinctly so clients can tell "upstream
+        // down" apart from "not found".
+        .map_err(|e| warp::reject::custom(UpstreamFetchError(e)))
+}
+
+/// POST /auth/login with JSON credentials; returns a signed JWT on success.
+fn login(creds: LoginRequest, config: Arc<AuthConfig>) -> Result<impl warp::Reply, Rejection> {
+    if creds.username != config.username || creds.password != config.password {
+        return Err(warp::reject::custom(Unauthorized));
+    }
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("clock before UNIX epoch")
+        .as_secs();
+    let claims = Claims {
+        sub: creds.username,
+        exp: (now + config.ttl_seconds) as usize,
+    };
+
+    let token = encode(&Header::default(), &claims, config.secret.as_bytes())
+        .map_err(|_| warp::reject::custom(Unauthorized))?;
+
+    Ok(warp::reply::json(&LoginResponse { token }))
+}
+
+/// A shared, cloneable client for the upstream posts service.
+///
+/// The `reqwest::async::Client` keeps a connection pool internally, so cloning
+/// is cheap and every request reuses the same pool. Results are memoized for a
+/// configurable TTL so bursts of traffic don't hammer the upstream.
+#[derive(Clone)]
+struct PostsClient {
+    client: reqwest::r#async::Client,
+    base_url: String,
+    ttl: Duration,
+    cache: Arc<Mutex<Option<(Instant, Vec<Post>)>>>,
+}
+
+impl PostsClient {
+    fn from_env() -> PostsClient {
+        let base_url = env::var("POSTS_UPSTREAM_URL")
+            .unwrap_or_else(|_| "http://jsonplaceholder.typicode.com".to_string());
+        let ttl = env::var("POSTS_CACHE_TTL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or_else(|| Duration::from_secs(60));
+        PostsClient {
+            client: reqwest::r#async::Client::new(),
+            base_url,
+            ttl,
+            cache: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Return the upstream posts, serving a cached copy while it is younger
+    /// than the configured TTL and re-fetching otherwise.
+    fn posts(&self) -> Box<dyn Future<Item = Vec<Post>, Error = FetchError> + Send> {
+        {
+            let cache = self.cache.lock().unwrap();
+            if let Some((fetched_at, ref posts)) = *cache {
+                if fetched_at.elapsed() < self.ttl {
+                    debug!("serving cached posts");
+                    return Box::new(future::ok(posts.clone()));
+                }
+            }
+        }
+
+        let url = format!("{}/posts", self.base_url);
+        let cache = self.cache.clone();
+        let fut = self
+            .client
+            .get(&url)
+            .send()
+            .and_then(|mut res| res.json::<Vec<Post>>())
+            .map_err(FetchError::from)
+            .map(move |posts| {
+                // Refresh the cache entry with the freshly fetched posts.
+                *cache.lock().unwrap() = Some((Instant::now(), posts.clone()));
+                posts
+            });
+
+        Box::new(fut)
+    }
 }
 
 // Define a type so we can return multiple types of errors
 enum FetchError {
-    Http(hyper::Error),
-    Json(serde_json::Error),
+    Request(reqwest::Error),
 }
 
-impl From<hyper::Error> for FetchError {
-    fn from(err: hyper::Error) -> FetchError {
-        FetchError::Http(err)
+impl From<reqwest::Error> for FetchError {
+    fn from(err: reqwest::Error) -> FetchError {
+        FetchError::Request(err)
     }
 }
 
-impl From<serde_json::Error> for FetchError {
-    fn from(err: serde_json::Error) -> FetchError {
-        FetchError::Json(err)
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FetchError::Request(e) => write!(f, "upstream request error: {}", e),
+        }
     }
 }
 
-/// POST /todos with JSON body
-fn create_todo(create: Todo, db: Db) -> Result<impl warp::Reply, warp::Rejection> {
-    debug!("create_todo: {:?}", create);
+impl std::error::Error for FetchError {}
 
-    let mut vec = db
-        .lock()
-        .unwrap();
+impl fmt::Debug for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
 
-    for todo in vec.iter() {
-        if todo.id == create.id {
-            debug!("    -> id already exists: {}", create.id);
-            // Todo with id already exists, return `400 BadRequest`.
-            return Ok(StatusCode::BAD_REQUEST);
-        }
+// Custom rejection types. Each one carries enough information for the
+// `handle_rejection` recover layer to build a structured JSON error body.
+
+/// A Todo was looked up by id but does not exist.
+#[derive(Debug)]
+struct TodoNotFound;
+
+impl fmt::Display for TodoNotFound {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "todo not found")
     }
+}
+
+impl std::error::Error for TodoNotFound {}
 
-    // No existing Todo with id, so insert and return `201 Created`.
-    vec.push(create);
+/// A Todo was created with an id that is already in use.
+#[derive(Debug)]
+struct TodoAlreadyExists;
 
-    Ok(StatusCode::CREATED)
+impl fmt::Display for TodoAlreadyExists {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "todo already exists")
+    }
 }
 
-/// PUT /todos/:id with JSON body
-fn update_todo(id: u64, update: Todo, db: Db) -> Result<impl warp::Reply, warp::Rejection> {
-    debug!("update_todo: id={}, todo={:?}", id, update);
-    let mut vec = db
-        .lock()
-        .unwrap();
-
-    // Look for the specified Todo...
-    for todo in vec.iter_mut() {
-        if todo.id == id {
-            *todo = update;
-            return Ok(warp::reply());
-        }
+impl std::error::Error for TodoAlreadyExists {}
+
+/// Fetching from the upstream posts service failed.
+#[derive(Debug)]
+struct UpstreamFetchError(FetchError);
+
+impl fmt::Display for UpstreamFetchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
     }
+}
+
+impl std::error::Error for UpstreamFetchError {}
 
-    debug!("    -> todo id not found!");
+/// The request lacked a valid bearer token for a protected endpoint.
+#[derive(Debug)]
+struct Unauthorized;
 
-    // If the for loop didn't return OK, then the ID doesn't exist...
-    Err(warp::reject::not_found())
+impl fmt::Display for Unauthorized {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unauthorized")
+    }
 }
 
-/// DELETE /todos/:id
-fn delete_todo(id: u64, db: Db) -> Result<impl warp::Reply, warp::Rejection> {
-    debug!("delete_todo: id={}", id);
+impl std::error::Error for Unauthorized {}
+
+/// The storage backend failed (e.g. the JSON file could not be written).
+#[derive(Debug)]
+struct StorageError;
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "storage error")
+    }
+}
+
+impl std::error::Error for StorageError {}
 
-    let mut vec = db
-        .lock()
-        .unwrap();
+/// Log an underlying storage error and turn it into a rejection.
+fn reject_store_error(err: io::Error) -> Rejection {
+    error!("storage error: {}", err);
+    warp::reject::custom(StorageError)
+}
+
+/// A stable, machine-parseable classification of an error. The string
+/// representation is what clients should match on, not the HTTP status.
+#[derive(Debug)]
+enum ErrorType {
+    NotFound,
+    AlreadyExists,
+    Upstream,
+    BadRequest,
+    Unauthorized,
+    MethodNotAllowed,
+    Internal,
+}
+
+impl ErrorType {
+    fn code(&self) -> &'static str {
+        match self {
+            ErrorType::NotFound => "NOT_FOUND",
+            ErrorType::AlreadyExists => "ALREADY_EXISTS",
+            ErrorType::Upstream => "UPSTREAM_ERROR",
+            ErrorType::BadRequest => "BAD_REQUEST",
+            ErrorType::Unauthorized => "UNAUTHORIZED",
+            ErrorType::MethodNotAllowed => "METHOD_NOT_ALLOWED",
+            ErrorType::Internal => "INTERNAL_ERROR",
+        }
+    }
+}
+
+/// The JSON body returned for every rejected request.
+#[derive(Serialize)]
+struct ErrorMessage {
+    code: u16,
+    error: &'static str,
+    message: String,
+}
 
-    let len = vec.len();
-    vec.retain(|todo| {
-        // Retain all Todos that aren't this id...
-        // In other words, remove all that *are* this id...
-        todo.id != id
+/// Maps a `Rejection` into a JSON `ErrorMessage` with the matching status.
+fn handle_rejection(err: Rejection) -> Result<impl Reply, Rejection> {
+    let (status, kind, message) = if err.is_not_found() {
+        (StatusCode::NOT_FOUND, ErrorType::NotFound, "not found".to_string())
+    } else if err.find_cause::<TodoNotFound>().is_some() {
+        (StatusCode::NOT_FOUND, ErrorType::NotFound, "todo not found".to_string())
+    } else if err.find_cause::<TodoAlreadyExists>().is_some() {
+        (StatusCode::BAD_REQUEST, ErrorType::AlreadyExists, "todo id already exists".to_string())
+    } else if let Some(e) = err.find_cause::<UpstreamFetchError>() {
+        (StatusCode::BAD_GATEWAY, ErrorType::Upstream, e.to_string())
+    } else if let Some(e) = err.find_cause::<warp::body::BodyDeserializeError>() {
+        (StatusCode::BAD_REQUEST, ErrorType::BadRequest, e.to_string())
+    } else if err.find_cause::<warp::reject::InvalidQuery>().is_some() {
+        (StatusCode::BAD_REQUEST, ErrorType::BadRequest, "invalid query string".to_string())
+    } else if err.find_cause::<warp::reject::PayloadTooLarge>().is_some() {
+        (StatusCode::BAD_REQUEST, ErrorType::BadRequest, "request body too large".to_string())
+    } else if err.find_cause::<Unauthorized>().is_some() {
+        // Checked *before* `MethodNotAllowed` on purpose: an unauthorized
+        // write rejects the protected branch with `Unauthorized` while the
+        // sibling `GET` branch rejects the same request with 405. warp's
+        // `Or` keeps both causes, so probing `Unauthorized` first pins the
+        // response to 401 rather than leaking a 405.
+        (StatusCode::UNAUTHORIZED, ErrorType::Unauthorized, "unauthorized".to_string())
+    } else if err.find_cause::<warp::reject::MethodNotAllowed>().is_some() {
+        (StatusCode::METHOD_NOT_ALLOWED, ErrorType::MethodNotAllowed, "method not allowed".to_string())
+    } else if err.find_cause::<StorageError>().is_some() {
+        (StatusCode::INTERNAL_SERVER_ERROR, ErrorType::Internal, "storage error".to_string())
+    } else {
+        error!("unhandled rejection: {:?}", err);
+        (StatusCode::INTERNAL_SERVER_ERROR, ErrorType::Internal, "internal server error".to_string())
+    };
+
+    let json = warp::reply::json(&ErrorMessage {
+        code: status.as_u16(),
+        error: kind.code(),
+        message,
     });
 
-    // If the vec is smaller, we found and deleted a Todo!
-    let deleted = vec.len() != len;
+    Ok(warp::reply::with_status(json, status))
+}
+
+/// POST /todos with JSON body
+fn create_todo(create: Todo, _claims: Claims, db: Db) -> impl Future<Item = impl warp::Reply, Error = Rejection> {
+    debug!("create_todo: {:?}", create);
+
+    db.create(create).then(|res| match res {
+        // No existing Todo with id, so it was inserted: `201 Created`.
+        Ok(true) => Ok(StatusCode::CREATED),
+        // Todo with id already exists, reject with a structured error.
+        Ok(false) => Err(warp::reject::custom(TodoAlreadyExists)),
+        Err(e) => Err(reject_store_error(e)),
+    })
+}
+
+/// PUT /todos/:id with JSON body
+fn update_todo(id: u64, update: Todo, _claims: Claims, db: Db) -> impl Future<Item = impl warp::Reply, Error = Rejection> {
+    debug!("update_todo: id={}, todo={:?}", id, update);
 
-    if deleted {
+    db.update(id, update).then(|res| match res {
+        Ok(true) => Ok(warp::reply()),
+        Ok(false) => {
+            debug!("    -> todo id not found!");
+            // The ID doesn't exist...
+            Err(warp::reject::custom(TodoNotFound))
+        }
+        Err(e) => Err(reject_store_error(e)),
+    })
+}
+
+/// DELETE /todos/:id
+fn delete_todo(id: u64, _claims: Claims, db: Db) -> impl Future<Item = impl warp::Reply, Error = Rejection> {
+    debug!("delete_todo: id={}", id);
+
+    db.delete(id).then(|res| match res {
         // respond with a `204 No Content`, which means successful,
         // yet no body expected...
-        Ok(StatusCode::NO_CONTENT)
-    } else {
-        debug!("    -> todo id not found!");
-        // Reject this request with a `404 Not Found`...
-        Err(warp::reject::not_found())
-    }
+        Ok(true) => Ok(StatusCode::NO_CONTENT),
+        Ok(false) => {
+            debug!("    -> todo id not found!");
+            // Reject this request with a structured `404 Not Found`...
+            Err(warp::reject::custom(TodoNotFound))
+        }
+        Err(e) => Err(reject_store_error(e)),
+    })
 }